@@ -0,0 +1,129 @@
+//! Thin, safe-to-call wrappers around every Win32 call this crate makes.
+//!
+//! The Wow64 FS-redirection and detection functions are resolved lazily via
+//! `GetProcAddress` rather than linked directly, since they are absent on platforms that
+//! have no need for them (32bit Windows, older systems, etc). Every `unsafe` block in the
+//! crate lives here, each carrying a `SAFETY` comment explaining why the call is sound.
+
+use std::ffi::{c_void, CString};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Once;
+
+use winapi::shared::minwindef::BOOL;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress};
+use winapi::um::processthreadsapi::GetCurrentProcess;
+use winapi::um::winnt::{HANDLE, PVOID};
+
+use crate::Error;
+
+type FnDisableFsRedirection = unsafe extern "system" fn(*mut PVOID) -> BOOL;
+type FnRevertFsRedirection = unsafe extern "system" fn(PVOID) -> BOOL;
+type FnIsWow64Process = unsafe extern "system" fn(HANDLE, *mut BOOL) -> BOOL;
+
+static DISABLE_FN: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static REVERT_FN: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static IS_WOW64_PROCESS_FN: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static RESOLVE: Once = Once::new();
+
+/// Looks up all three functions exactly once and caches the results (a null entry means
+/// the symbol wasn't found).
+///
+/// SAFETY: `GetModuleHandleA`/`GetProcAddress` are safe to call with a valid,
+/// NUL-terminated name; `kernel32` is already loaded and pinned in every process, so the
+/// returned module handle needs no `FreeLibrary`.
+fn resolve() {
+    RESOLVE.call_once(|| unsafe {
+        let module = GetModuleHandleA(CString::new("kernel32").unwrap().as_ptr());
+        if module.is_null() {
+            return;
+        }
+
+        let disable = GetProcAddress(
+            module,
+            CString::new("Wow64DisableWow64FsRedirection").unwrap().as_ptr(),
+        );
+        if !disable.is_null() {
+            DISABLE_FN.store(disable as *mut c_void, Ordering::SeqCst);
+        }
+
+        let revert = GetProcAddress(
+            module,
+            CString::new("Wow64RevertWow64FsRedirection").unwrap().as_ptr(),
+        );
+        if !revert.is_null() {
+            REVERT_FN.store(revert as *mut c_void, Ordering::SeqCst);
+        }
+
+        let is_wow64_process =
+            GetProcAddress(module, CString::new("IsWow64Process").unwrap().as_ptr());
+        if !is_wow64_process.is_null() {
+            IS_WOW64_PROCESS_FN.store(is_wow64_process as *mut c_void, Ordering::SeqCst);
+        }
+    });
+}
+
+/// Returns the code `GetLastError` reports for the most recent failed Win32 call on this
+/// thread.
+///
+/// SAFETY: `GetLastError` takes no arguments and is always safe to call.
+fn last_error() -> u32 {
+    unsafe { GetLastError() }
+}
+
+/// Disables Wow64 FS redirection for the current thread, returning the opaque previous
+/// state that must be passed to [`revert_fs_redirection`] to undo it.
+pub(crate) fn disable_fs_redirection() -> Result<PVOID, Error> {
+    resolve();
+    let ptr = DISABLE_FN.load(Ordering::SeqCst);
+    if ptr.is_null() {
+        return Err(Error::FunctionUnavailable);
+    }
+
+    // SAFETY: `ptr` was resolved via `GetProcAddress` against the well-known
+    // `Wow64DisableWow64FsRedirection` signature (`BOOL(*)(PVOID*)`), and `old` is a
+    // valid out-pointer to a local `PVOID`.
+    let disable: FnDisableFsRedirection = unsafe { std::mem::transmute(ptr) };
+    let mut old: PVOID = ptr::null_mut();
+    match unsafe { disable(&mut old) } {
+        1 => Ok(old),
+        _ => Err(Error::DisableFailed(last_error())),
+    }
+}
+
+/// Reverts Wow64 FS redirection previously disabled by [`disable_fs_redirection`].
+pub(crate) fn revert_fs_redirection(old: PVOID) -> Result<(), Error> {
+    resolve();
+    let ptr = REVERT_FN.load(Ordering::SeqCst);
+    if ptr.is_null() {
+        return Err(Error::FunctionUnavailable);
+    }
+
+    // SAFETY: `ptr` was resolved via `GetProcAddress` against the well-known
+    // `Wow64RevertWow64FsRedirection` signature (`BOOL(*)(PVOID)`); `old` must be a value
+    // previously returned by `disable_fs_redirection` on this thread, which every caller
+    // in this crate upholds.
+    let revert: FnRevertFsRedirection = unsafe { std::mem::transmute(ptr) };
+    match unsafe { revert(old) } {
+        1 => Ok(()),
+        _ => Err(Error::RevertFailed(last_error())),
+    }
+}
+
+/// Returns whether the current process is running under WoW64. `false` if the detection
+/// function isn't available (e.g. plain 32bit Windows).
+pub(crate) fn is_wow64_process() -> bool {
+    resolve();
+    let ptr = IS_WOW64_PROCESS_FN.load(Ordering::SeqCst);
+    if ptr.is_null() {
+        return false;
+    }
+
+    // SAFETY: `ptr` was resolved via `GetProcAddress` against the well-known
+    // `IsWow64Process` signature (`BOOL(*)(HANDLE, PBOOL)`); `GetCurrentProcess` returns
+    // a pseudo-handle that is always valid and requires no `CloseHandle`.
+    let is_wow64_process: FnIsWow64Process = unsafe { std::mem::transmute(ptr) };
+    let mut result: BOOL = 0;
+    unsafe { is_wow64_process(GetCurrentProcess(), &mut result) == 1 && result != 0 }
+}
@@ -55,16 +55,85 @@
 #[macro_use]
 extern crate log;
 
-use winapi::um::errhandlingapi::GetLastError;
+mod api;
+mod native_path;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::marker::PhantomData;
+
 use winapi::um::winnt::PVOID;
-use winapi::um::wow64apiset::{Wow64DisableWow64FsRedirection, Wow64RevertWow64FsRedirection};
 
-/// Wrapper around pointer to file system redirection state
-pub struct DisableFsRedirection(Option<*mut PVOID>);
+pub use native_path::native_path;
+
+/// Errors that can occur while disabling or reverting Wow64 file system redirection.
+#[derive(Debug)]
+pub enum Error {
+    /// `kernel32` doesn't export the Wow64 FS-redirection functions on this system
+    /// (e.g. 32bit Windows, or a system without WoW64 support).
+    FunctionUnavailable,
+    /// The current process isn't running under WoW64, so there is no redirection to
+    /// disable. See [`is_wow64`].
+    NotWow64Process,
+    /// `Wow64DisableWow64FsRedirection` failed; contains the `GetLastError` code.
+    DisableFailed(u32),
+    /// `Wow64RevertWow64FsRedirection` failed; contains the `GetLastError` code.
+    RevertFailed(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FunctionUnavailable => {
+                write!(f, "Wow64 FS-redirection functions are not available on this system")
+            }
+            Error::NotWow64Process => write!(f, "the current process is not running under WoW64"),
+            Error::DisableFailed(code) => write!(f, "disabling FS redirection failed: {}", code),
+            Error::RevertFailed(code) => write!(f, "reverting FS redirection failed: {}", code),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Returns `true` if the current process is a 32bit process running under WoW64 on a
+/// 64bit Windows. FS redirection only applies in that case; on native 64bit or plain
+/// 32bit Windows this returns `false` and [`DisableFsRedirection::start`] returns
+/// [`Error::NotWow64Process`] without touching redirection.
+pub fn is_wow64() -> bool {
+    api::is_wow64_process()
+}
+
+/// The previous redirection state for the current thread, plus how many nested guards
+/// are currently relying on it.
+struct NestedState {
+    depth: u32,
+    old: PVOID,
+}
+
+thread_local! {
+    static STATE: RefCell<Option<NestedState>> = RefCell::new(None);
+}
+
+/// A guard marking that Wow64 FS redirection is disabled for the current thread.
+///
+/// Guards nest: the first `start()` on a thread actually disables redirection and
+/// stashes the previous state; further `start()`s while one is already active just
+/// increment a depth counter. The real `Wow64RevertWow64FsRedirection` call only
+/// happens when the outermost guard drops (even during a panic unwind), avoiding the
+/// "disable/enable cannot be combined" pitfalls of nesting the raw Win32 calls directly.
+///
+/// The guard is tied to the thread-local `STATE` it was created against, so it must not
+/// be moved to (and dropped on) another thread; the `PhantomData<*const ()>` makes it
+/// `!Send`/`!Sync` to enforce that.
+pub struct DisableFsRedirection(PhantomData<*const ()>);
 
 impl DisableFsRedirection {
     /// Returns a `Result` containing either a `DisableFsRedirection` or
-    /// an `Error<u32>` with the error code from Windows.
+    /// an `Error` describing why redirection could not be disabled.
+    ///
+    /// Returns `Error::NotWow64Process` on hosts where [`is_wow64`] is `false`, since
+    /// there is no redirection to disable there.
     ///
     /// # Examples
     ///
@@ -73,32 +142,87 @@ impl DisableFsRedirection {
     ///     // access normally redirected files
     /// });
     /// ```
-    pub fn start() -> Result<DisableFsRedirection, u32> {
-        let mut old: PVOID = unsafe { std::mem::zeroed() };
-        match unsafe { Wow64DisableWow64FsRedirection(&mut old) } {
-            1 => Ok(DisableFsRedirection(Some(&mut old))),
-            _ => Err(unsafe { GetLastError() }),
+    pub fn start() -> Result<DisableFsRedirection, Error> {
+        if !is_wow64() {
+            return Err(Error::NotWow64Process);
         }
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            match state.as_mut() {
+                Some(nested) => {
+                    nested.depth += 1;
+                    Ok(())
+                }
+                None => {
+                    let old = api::disable_fs_redirection()?;
+                    *state = Some(NestedState { depth: 1, old });
+                    Ok(())
+                }
+            }
+        })?;
+
+        Ok(DisableFsRedirection(PhantomData))
     }
 }
 
 impl Drop for DisableFsRedirection {
     fn drop(&mut self) {
-        if let Some(h) = self.0 {
-            if unsafe { Wow64RevertWow64FsRedirection(*h) } != 1 {
-                error!("Revert of file system redirection failed with {}", unsafe {
-                    GetLastError()
-                });
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let nested = match state.as_mut() {
+                Some(nested) => nested,
+                None => return,
+            };
+
+            nested.depth -= 1;
+            if nested.depth > 0 {
+                return;
             }
-        }
+
+            let old = state.take().unwrap().old;
+            if let Err(e) = api::revert_fs_redirection(old) {
+                error!("Revert of file system redirection failed: {}", e);
+            }
+        });
     }
 }
 
-#[cfg(all(test, windows, target_pointer_width = "32"))]
+/// Disables Wow64 FS redirection, runs `f`, and re-enables redirection before
+/// returning, whether `f` returns normally or panics.
+///
+/// This is the preferred way to use this crate: disable redirection, perform the
+/// minimal amount of I/O needed, and let the guard revert immediately afterwards.
+///
+/// # Examples
+///
+/// ```no_run
+/// use win_fs_redirect::with_redirection_disabled;
+///
+/// let size = with_redirection_disabled(|| {
+///     std::fs::metadata("c:\\windows\\system32\\kernel32.dll").map(|m| m.len())
+/// });
+/// ```
+pub fn with_redirection_disabled<F, R>(f: F) -> Result<R, Error>
+where
+    F: FnOnce() -> R,
+{
+    let _guard = DisableFsRedirection::start()?;
+    Ok(f())
+}
+
+#[cfg(all(test, windows))]
 mod tests {
     #[test]
     fn kernel32_size() {
         use crate::DisableFsRedirection;
+
+        if !crate::is_wow64() {
+            // Nothing to exercise on a non-WoW64 host; `start()` is documented to return
+            // `Error::NotWow64Process` here.
+            return;
+        }
+
         let s = std::fs::metadata("c:\\windows\\system32\\kernel32.dll")
             .unwrap()
             .len();
@@ -112,4 +236,37 @@ mod tests {
             .map_err(|_| assert!(false))
             .unwrap();
     }
+
+    #[test]
+    fn nested_guards_revert_only_on_outermost_drop() {
+        use crate::DisableFsRedirection;
+
+        if !crate::is_wow64() {
+            // Nothing to exercise on a non-WoW64 host; `start()` is documented to return
+            // `Error::NotWow64Process` here.
+            return;
+        }
+
+        let redirected_size = || {
+            std::fs::metadata("c:\\windows\\system32\\kernel32.dll")
+                .unwrap()
+                .len()
+        };
+
+        let original = redirected_size();
+
+        let outer = DisableFsRedirection::start().unwrap();
+        let disabled = redirected_size();
+        assert!(disabled != original);
+
+        let inner = DisableFsRedirection::start().unwrap();
+        drop(inner);
+        // The inner guard was nested, so dropping it must not revert redirection while
+        // the outer guard is still alive.
+        assert_eq!(disabled, redirected_size());
+
+        drop(outer);
+        // Only the outermost guard's drop actually reverts.
+        assert_eq!(original, redirected_size());
+    }
 }
@@ -0,0 +1,103 @@
+//! Redirection-free path translation via the `Sysnative` alias.
+//!
+//! Disabling FS redirection for a thread is risky because it also affects DLL loading
+//! and delay-loaded imports for as long as it's active. For the common case of just
+//! wanting to read the real 64bit file, WOW64 exposes a `Sysnative` pseudo-directory
+//! that always resolves to the true `System32`, bypassing redirection without touching
+//! any global state.
+
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// Rewrites references to `%SystemRoot%\System32` into `%SystemRoot%\Sysnative`, so the
+/// real 64bit file can be reached without calling [`DisableFsRedirection::start`].
+///
+/// This is a no-op (`path` is returned unchanged):
+/// - when the current process isn't running under WoW64 (see [`is_wow64`]), since there
+///   is no redirection to work around there;
+/// - when `path` doesn't point inside `%SystemRoot%\System32`.
+///
+/// [`DisableFsRedirection::start`]: crate::DisableFsRedirection::start
+/// [`is_wow64`]: crate::is_wow64
+pub fn native_path(path: &Path) -> PathBuf {
+    if !crate::is_wow64() {
+        return path.to_path_buf();
+    }
+
+    let system_root = match env::var_os("SystemRoot") {
+        Some(root) => PathBuf::from(root),
+        None => return path.to_path_buf(),
+    };
+    let system32 = system_root.join("System32");
+
+    match strip_prefix_ignore_case(path, &system32) {
+        Some(rest) => system_root.join("Sysnative").join(rest),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Like `Path::strip_prefix`, but compares components case-insensitively, matching
+/// Windows' own case-insensitive (if case-preserving) file system semantics.
+fn strip_prefix_ignore_case<'a>(path: &'a Path, prefix: &Path) -> Option<&'a Path> {
+    let mut path_components = path.components();
+    for prefix_component in prefix.components() {
+        match path_components.next() {
+            Some(component) if components_eq_ignore_case(component, prefix_component) => {}
+            _ => return None,
+        }
+    }
+    Some(path_components.as_path())
+}
+
+fn components_eq_ignore_case(a: Component, b: Component) -> bool {
+    a.as_os_str()
+        .to_string_lossy()
+        .eq_ignore_ascii_case(&b.as_os_str().to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_prefix_ignore_case;
+    use std::path::Path;
+
+    #[test]
+    fn matches_regardless_of_case() {
+        let path = Path::new(r"C:\Windows\System32\kernel32.dll");
+        let prefix = Path::new(r"c:\windows\system32");
+        assert_eq!(
+            strip_prefix_ignore_case(path, prefix),
+            Some(Path::new("kernel32.dll"))
+        );
+    }
+
+    #[test]
+    fn matches_mixed_slash_styles() {
+        let path = Path::new(r"C:\Windows\System32\kernel32.dll");
+        let prefix = Path::new("C:/Windows/System32");
+        assert_eq!(
+            strip_prefix_ignore_case(path, prefix),
+            Some(Path::new("kernel32.dll"))
+        );
+    }
+
+    #[test]
+    fn path_equal_to_prefix_strips_to_empty() {
+        let path = Path::new(r"C:\Windows\System32");
+        let prefix = Path::new(r"C:\Windows\System32");
+        assert_eq!(strip_prefix_ignore_case(path, prefix), Some(Path::new("")));
+    }
+
+    #[test]
+    fn path_outside_prefix_is_not_stripped() {
+        let path = Path::new(r"C:\Windows\SysWOW64\kernel32.dll");
+        let prefix = Path::new(r"C:\Windows\System32");
+        assert_eq!(strip_prefix_ignore_case(path, prefix), None);
+    }
+
+    #[test]
+    fn path_shorter_than_prefix_is_not_stripped() {
+        let path = Path::new(r"C:\Windows");
+        let prefix = Path::new(r"C:\Windows\System32");
+        assert_eq!(strip_prefix_ignore_case(path, prefix), None);
+    }
+}